@@ -15,11 +15,15 @@
  * ------------------------------------------------------------------------------
  */
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::mpsc::Sender;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use crate::block::Block;
+use crate::journal::block_validation_stage::{
+    ExecutionPending, RelevancyChecked, SignatureVerified, ValidationStage,
+};
 use crate::journal::block_validator::{BlockStatusStore, BlockValidationResult};
 use crate::journal::block_wrapper::BlockStatus;
 use crate::journal::chain::COMMIT_STORE;
@@ -33,6 +37,43 @@ lazy_static! {
         metrics::get_collector("sawtooth_validator.block_validator");
 }
 
+/// Default amount of time a block may sit in `pending` waiting for its
+/// predecessor before `prune_stale` evicts it.
+const DEFAULT_PENDING_TTL: Duration = Duration::from_secs(300);
+
+/// Bookkeeping kept alongside a block parked in `pending`, so `prune_stale`
+/// can tell how long it has been waiting on its predecessor.
+#[derive(Clone, Debug)]
+struct PendingMetadata {
+    previous_block_id: String,
+    first_seen: Instant,
+    attempts: u32,
+}
+
+/// Ranks blocks that became ready for validation at the same time, so that
+/// validation effort is spent on the branch most likely to become canonical
+/// first instead of in arbitrary `HashMap`/`HashSet` order.
+pub trait ForkPreference: Send + Sync {
+    /// Order `blocks` from highest to lowest validation priority.
+    fn sort(&self, blocks: &mut Vec<Block>);
+}
+
+/// Default `ForkPreference`: prefer the greatest `block_num` (the branch
+/// extending furthest past the current chain head), with ties broken
+/// deterministically by `header_signature` so ordering is reproducible.
+#[derive(Clone, Copy, Default)]
+pub struct BlockNumForkPreference;
+
+impl ForkPreference for BlockNumForkPreference {
+    fn sort(&self, blocks: &mut Vec<Block>) {
+        blocks.sort_by(|a, b| {
+            b.block_num
+                .cmp(&a.block_num)
+                .then_with(|| a.header_signature.cmp(&b.header_signature))
+        });
+    }
+}
+
 #[derive(Clone)]
 pub struct BlockScheduler<B: BlockStatusStore> {
     state: Arc<Mutex<BlockSchedulerState<B>>>,
@@ -58,10 +99,70 @@ impl<B: BlockStatusStore> BlockScheduler<B> {
                 processing: HashSet::new(),
                 descendants_by_previous_id: HashMap::new(),
                 results_sender: None,
+                stage_by_block_id: HashMap::new(),
+                fork_preference: Box::new(BlockNumForkPreference),
+                pending_metadata: HashMap::new(),
+                pending_ttl: DEFAULT_PENDING_TTL,
+                needs_predecessor_sender: None,
+                max_in_flight: None,
+                admission_queue: VecDeque::new(),
             })),
         }
     }
 
+    /// Cap how many blocks may be in `processing` at once. Newly-ready
+    /// blocks beyond the cap are held in an ordered admission queue and
+    /// released by `done` as slots free up, giving the scheduler explicit
+    /// backpressure so validation throughput stays matched to executor
+    /// capacity. `None` (the default) leaves the set unbounded. Raising the
+    /// cap does not itself release queued blocks; they drain as subsequent
+    /// `done` calls free up slots.
+    pub fn set_max_in_flight(&self, max_in_flight: Option<usize>) {
+        self.state
+            .lock()
+            .expect("The BlockScheduler Mutex was poisoned")
+            .max_in_flight = max_in_flight;
+    }
+
+    /// Override the ranking used to order blocks that become ready for
+    /// validation at the same time. Defaults to `BlockNumForkPreference`.
+    pub fn set_fork_preference(&self, fork_preference: Box<dyn ForkPreference>) {
+        self.state
+            .lock()
+            .expect("The BlockScheduler Mutex was poisoned")
+            .fork_preference = fork_preference;
+    }
+
+    /// Set how long a block may sit in `pending` waiting on its predecessor
+    /// before `prune_stale` evicts it. Defaults to `DEFAULT_PENDING_TTL`.
+    pub fn set_pending_ttl(&self, ttl: Duration) {
+        self.state
+            .lock()
+            .expect("The BlockScheduler Mutex was poisoned")
+            .pending_ttl = ttl;
+    }
+
+    /// Register a channel that receives blocks evicted by `prune_stale`
+    /// because their predecessor never arrived, so the caller can re-request
+    /// the missing predecessor or drop the block.
+    pub fn set_needs_predecessor_sender(&self, sender: Sender<Block>) {
+        self.state
+            .lock()
+            .expect("The BlockScheduler Mutex was poisoned")
+            .needs_predecessor_sender = Some(sender);
+    }
+
+    /// Evict pending blocks that have exceeded `pending_ttl` waiting for
+    /// their predecessor, sending each through the "needs predecessor"
+    /// channel and recursively dropping any descendants left orphaned by the
+    /// eviction.
+    pub fn prune_stale(&self, now: Instant) {
+        self.state
+            .lock()
+            .expect("The BlockScheduler Mutex was poisoned")
+            .prune_stale(now);
+    }
+
     pub fn set_results_sender(&self, sender: Sender<BlockValidationResult>) {
         self.state
             .lock()
@@ -78,6 +179,25 @@ impl<B: BlockStatusStore> BlockScheduler<B> {
             .schedule(blocks)
     }
 
+    /// Schedule an already-ordered, contiguous ancestor-to-descendant chain
+    /// segment (as produced by sync catch-up) in a single pass, returning the
+    /// contiguous prefix that is immediately ready to validate. Unlike
+    /// `schedule`, this does not walk `block_manager.branch` on a cache miss:
+    /// a segment whose anchor has not resolved is parked in `pending` block
+    /// by block, each linked under its predecessor in
+    /// `descendants_by_previous_id`. A non-contiguous segment is rejected
+    /// outright. A segment whose anchor is already known invalid is
+    /// invalidated as a whole; with a valid anchor, the first block already
+    /// known invalid has invalidation propagated through the rest of the
+    /// suffix. Blocks of a parked segment are not checked for known
+    /// invalidity.
+    pub fn schedule_segment(&self, segment: Vec<Block>) -> Vec<Block> {
+        self.state
+            .lock()
+            .expect("The BlockScheduler Mutex was poisoned")
+            .schedule_segment(segment)
+    }
+
     /// Mark the block associated with block_id as having completed block
     /// validation, returning any descendants marked for processing.
     /// Will remove block_id from processing, take all descendants, and move
@@ -95,6 +215,55 @@ impl<B: BlockStatusStore> BlockScheduler<B> {
             .expect("The BlockScheduler Mutex was poisoned")
             .contains(block_id)
     }
+
+    /// The highest validation stage reached by `block_id` so far. A block
+    /// that re-enters scheduling after a cache miss or fork re-org resumes
+    /// from here instead of re-validating from scratch.
+    pub fn validation_stage(&self, block_id: &str) -> ValidationStage {
+        self.state
+            .lock()
+            .expect("The BlockScheduler Mutex was poisoned")
+            .validation_stage(block_id)
+    }
+
+    /// Record that a block has been relevancy-checked, provided that is
+    /// further along than whatever was previously recorded. Takes a
+    /// `RelevancyChecked` rather than a bare `ValidationStage` so the
+    /// compiler, not a runtime check, prevents the chain controller's
+    /// validation pipeline from recording this stage without actually
+    /// having performed the check.
+    pub fn mark_relevancy_checked(&self, checked: &RelevancyChecked) {
+        self.state
+            .lock()
+            .expect("The BlockScheduler Mutex was poisoned")
+            .advance_stage(&checked.block().header_signature, ValidationStage::Relevancy);
+    }
+
+    /// Record that a block's signatures have been verified. Only
+    /// constructible from a `RelevancyChecked` block, so this stage cannot
+    /// be recorded out of order.
+    pub fn mark_signature_verified(&self, verified: &SignatureVerified) {
+        self.state
+            .lock()
+            .expect("The BlockScheduler Mutex was poisoned")
+            .advance_stage(
+                &verified.block().header_signature,
+                ValidationStage::SignatureVerified,
+            );
+    }
+
+    /// Record that a block is ready for execution. Only constructible from
+    /// a `SignatureVerified` block, so this stage cannot be recorded out of
+    /// order.
+    pub fn mark_execution_pending(&self, pending: &ExecutionPending) {
+        self.state
+            .lock()
+            .expect("The BlockScheduler Mutex was poisoned")
+            .advance_stage(
+                &pending.block().header_signature,
+                ValidationStage::ExecutionPending,
+            );
+    }
 }
 
 struct BlockSchedulerState<B: BlockStatusStore> {
@@ -104,6 +273,23 @@ struct BlockSchedulerState<B: BlockStatusStore> {
     pub processing: HashSet<String>,
     pub descendants_by_previous_id: HashMap<String, Vec<Block>>,
     results_sender: Option<Sender<BlockValidationResult>>,
+    /// Highest `ValidationStage` reached per block, so that a block which
+    /// re-enters scheduling resumes instead of re-validating from scratch.
+    stage_by_block_id: HashMap<String, ValidationStage>,
+    /// Ranks blocks that become ready for validation at the same time.
+    fork_preference: Box<dyn ForkPreference>,
+    /// Tracks how long each pending block has been waiting on its
+    /// predecessor, keyed by the pending block's own id.
+    pending_metadata: HashMap<String, PendingMetadata>,
+    /// How long a block may sit in `pending` before `prune_stale` evicts it.
+    pending_ttl: Duration,
+    needs_predecessor_sender: Option<Sender<Block>>,
+    /// Maximum number of blocks allowed in `processing` at once. `None`
+    /// leaves it unbounded.
+    max_in_flight: Option<usize>,
+    /// Blocks that became ready while `processing` was at `max_in_flight`,
+    /// released into `processing` in order as `done` frees up slots.
+    admission_queue: VecDeque<Block>,
 }
 
 impl<B: BlockStatusStore> BlockSchedulerState<B> {
@@ -123,6 +309,7 @@ impl<B: BlockStatusStore> BlockSchedulerState<B> {
                     "During block scheduling, block already in pending: {}",
                     &block.header_signature
                 );
+                self.touch_pending(&block.header_signature);
                 continue;
             }
 
@@ -147,9 +334,12 @@ impl<B: BlockStatusStore> BlockSchedulerState<B> {
 
             //up to this point block and pred are not in validation
             if block.previous_block_id == NULL_BLOCK_IDENTIFIER {
-                debug!("Adding block {} for processing", &block.header_signature);
-                self.processing.insert(block.header_signature.clone());
-                ready.push(block);
+                debug!(
+                    "Adding block {} for processing, resuming from stage {:?}",
+                    &block.header_signature,
+                    self.validation_stage(&block.header_signature)
+                );
+                self.admit(block, &mut ready);
                 return ready;
             }
 
@@ -157,10 +347,13 @@ impl<B: BlockStatusStore> BlockSchedulerState<B> {
 
             match prev_block_validity {
                 BlockStatus::Valid => {
-                    debug!("Adding block {} for processing", &block.header_signature);
+                    debug!(
+                        "Adding block {} for processing, resuming from stage {:?}",
+                        &block.header_signature,
+                        self.validation_stage(&block.header_signature)
+                    );
 
-                    self.processing.insert(block.header_signature.clone());
-                    ready.push(block);
+                    self.admit(block, &mut ready);
                 }
                 // pred results not found though
                 BlockStatus::Unknown => {
@@ -239,9 +432,170 @@ impl<B: BlockStatusStore> BlockSchedulerState<B> {
             }
         }
         self.update_gauges();
+        self.fork_preference.sort(&mut ready);
         ready
     }
 
+    fn schedule_segment(&mut self, segment: Vec<Block>) -> Vec<Block> {
+        if segment.is_empty() {
+            return vec![];
+        }
+
+        for pair in segment.windows(2) {
+            if pair[1].previous_block_id != pair[0].header_signature {
+                warn!(
+                    "Rejecting chain segment: block {} does not extend block {}",
+                    &pair[1].header_signature, &pair[0].header_signature
+                );
+                return vec![];
+            }
+        }
+
+        let segment: Vec<Block> = segment
+            .into_iter()
+            .filter(|block| {
+                let known = self.contains(&block.header_signature);
+                if known {
+                    debug!(
+                        "During segment scheduling, block already scheduled: {}",
+                        &block.header_signature
+                    );
+                }
+                !known
+            })
+            .collect();
+
+        if segment.is_empty() {
+            return vec![];
+        }
+
+        // Filtering out already-known blocks can open a gap in the middle of
+        // the segment (e.g. a sibling path already scheduled an interior
+        // block), leaving a remainder that is no longer actually contiguous.
+        // Re-check rather than admit a block whose real predecessor was
+        // filtered out without having resolved.
+        for pair in segment.windows(2) {
+            if pair[1].previous_block_id != pair[0].header_signature {
+                warn!(
+                    "Rejecting chain segment after dedup: block {} no longer extends block {}",
+                    &pair[1].header_signature, &pair[0].header_signature
+                );
+                return vec![];
+            }
+        }
+
+        let anchor_status = self.block_validity(&segment[0].previous_block_id);
+
+        if anchor_status == BlockStatus::Invalid {
+            self.invalidate_segment(&segment);
+            self.update_gauges();
+            return vec![];
+        }
+
+        if anchor_status != BlockStatus::Valid && segment[0].previous_block_id != NULL_BLOCK_IDENTIFIER
+        {
+            // The anchor hasn't resolved yet; park the whole segment as a
+            // chain of pending descendants in one pass, without the branch
+            // walk `schedule` does on a cache miss. Each block is admitted
+            // once `done` is called for its predecessor.
+            debug!(
+                "During segment scheduling, anchor {} status is unknown, parking segment of {} blocks",
+                &segment[0].previous_block_id,
+                segment.len()
+            );
+            for block in segment {
+                self.add_block_to_pending(block);
+            }
+            self.update_gauges();
+            return vec![];
+        }
+
+        // Anchor is known-valid (or this segment starts the chain): admit
+        // the contiguous prefix up to the first already-known-invalid block.
+        let mut ready = vec![];
+        let mut invalid_from = None;
+        for (index, block) in segment.iter().enumerate() {
+            if self.block_status_store.status(&block.header_signature) == BlockStatus::Invalid {
+                invalid_from = Some(index);
+                break;
+            }
+            if !self.has_capacity() {
+                // The suffix can't be admitted into `processing` right now,
+                // but it may still contain a block already known invalid.
+                // `release_admitted` only re-checks capacity, not validity,
+                // before promoting a queued block into `processing`, so an
+                // invalid block has to be found here, before queuing,
+                // rather than left to silently ride along with the rest of
+                // the suffix.
+                let suffix = &segment[index..];
+                match suffix
+                    .iter()
+                    .position(|block| {
+                        self.block_status_store.status(&block.header_signature)
+                            == BlockStatus::Invalid
+                    }) {
+                    Some(offset) => {
+                        debug!(
+                            "Processing at max_in_flight capacity, queuing {} remaining segment block(s) for admission",
+                            offset
+                        );
+                        self.admission_queue.extend(suffix[..offset].iter().cloned());
+                        invalid_from = Some(index + offset);
+                    }
+                    None => {
+                        // Keep the contiguous suffix in order in the
+                        // admission queue rather than admitting it out of
+                        // sequence later.
+                        debug!(
+                            "Processing at max_in_flight capacity, queuing {} remaining segment block(s) for admission",
+                            suffix.len()
+                        );
+                        self.admission_queue.extend(suffix.iter().cloned());
+                    }
+                }
+                break;
+            }
+            debug!(
+                "Adding block {} for processing as part of a chain segment, resuming from stage {:?}",
+                &block.header_signature,
+                self.validation_stage(&block.header_signature)
+            );
+            self.processing.insert(block.header_signature.clone());
+            ready.push(block.clone());
+        }
+
+        if let Some(index) = invalid_from {
+            self.invalidate_segment(&segment[index..]);
+        }
+
+        self.update_gauges();
+        ready
+    }
+
+    /// Mark every block in `segment` invalid and send the result downstream,
+    /// used to propagate invalidation through the rest of a chain segment
+    /// once an invalid ancestor is found.
+    fn invalidate_segment(&mut self, segment: &[Block]) {
+        for block in segment {
+            self.processing.insert(block.header_signature.clone());
+            self.results_sender
+                .as_ref()
+                .expect("Results' tx is not supposed to be None")
+                .send(BlockValidationResult {
+                    block_id: block.header_signature.clone(),
+                    execution_results: vec![],
+                    num_transactions: 0,
+                    status: BlockStatus::Invalid,
+                })
+                .expect("Failed to send invalid block to results thread in the chain controller");
+
+            debug!(
+                "Block {} has an invalid ancestor, propagating invalidation through chain segment",
+                &block.header_signature
+            );
+        }
+    }
+
     fn block_validity(&self, block_id: &str) -> BlockStatus {
         let status = self.block_status_store.status(block_id);
         if status == BlockStatus::Unknown {
@@ -265,24 +619,42 @@ impl<B: BlockStatusStore> BlockSchedulerState<B> {
     /// When a block is marked invalid, thus all descendants are invalid, do not process them.
     fn done(&mut self, block_id: &str, mark_descendants_invalid: bool) -> Vec<Block> {
         self.processing.remove(block_id);
-        let ready = self
+        // The block has finished validation, so its recorded progress is no
+        // longer needed; drop it to keep the map from growing unbounded.
+        self.stage_by_block_id.remove(block_id);
+        let descendants = self
             .descendants_by_previous_id
             .remove(block_id)
             .unwrap_or_default();
 
-        for blk in &ready {
+        let mut ready = vec![];
+        for blk in descendants {
             self.pending.remove(&blk.header_signature);
-            if !mark_descendants_invalid {
-                self.processing.insert(blk.header_signature.clone());
-            } else {
+            // No longer waiting in pending, so its TTL bookkeeping is moot.
+            self.pending_metadata.remove(&blk.header_signature);
+            if mark_descendants_invalid {
                 info!(
                     "Predecessor {} marked invalid, marking descendant {} invalid",
                     block_id, &blk.header_signature
                 );
+                // Invalidated descendants are handed back so the caller can
+                // cascade the invalidation, but they never occupy a
+                // `processing` slot.
+                ready.push(blk);
+            } else {
+                // Route through `admit` so a burst of siblings freed up by
+                // this one `done` call still respects `max_in_flight`
+                // instead of all landing in `processing` unconditionally.
+                self.admit(blk, &mut ready);
             }
         }
 
+        // A slot just freed up (and any marked-invalid descendants never
+        // actually consumed one); release queued blocks while capacity lasts.
+        self.release_admitted(&mut ready);
+
         self.update_gauges();
+        self.fork_preference.sort(&mut ready);
         ready
     }
 
@@ -290,10 +662,35 @@ impl<B: BlockStatusStore> BlockSchedulerState<B> {
         self.pending.contains(block_id) || self.processing.contains(block_id)
     }
 
+    fn validation_stage(&self, block_id: &str) -> ValidationStage {
+        self.stage_by_block_id
+            .get(block_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn advance_stage(&mut self, block_id: &str, stage: ValidationStage) {
+        let recorded = self
+            .stage_by_block_id
+            .entry(block_id.to_string())
+            .or_insert(ValidationStage::Unchecked);
+        if stage > *recorded {
+            *recorded = stage;
+        }
+    }
+
     ///insert into pending and get back the pred's descendants, if its is not already there,
     /// insert it.
     fn add_block_to_pending(&mut self, block: Block) {
         self.pending.insert(block.header_signature.clone());
+        self.pending_metadata
+            .entry(block.header_signature.clone())
+            .or_insert_with(|| PendingMetadata {
+                previous_block_id: block.previous_block_id.clone(),
+                first_seen: Instant::now(),
+                attempts: 1,
+            });
+
         if let Some(ref mut waiting_descendants) = self
             .descendants_by_previous_id
             .get_mut(&block.previous_block_id)
@@ -308,11 +705,131 @@ impl<B: BlockStatusStore> BlockSchedulerState<B> {
             .insert(block.previous_block_id.clone(), vec![block]);
     }
 
+    /// Record another attempt to schedule a block that is still waiting in
+    /// `pending`, without resetting its TTL clock.
+    fn touch_pending(&mut self, block_id: &str) {
+        if let Some(meta) = self.pending_metadata.get_mut(block_id) {
+            meta.attempts += 1;
+        }
+    }
+
+    /// Evict blocks that have been waiting in `pending` for longer than
+    /// `pending_ttl`, handing each to `needs_predecessor_sender` so the
+    /// caller can re-request the missing predecessor or give up on it, and
+    /// recursively dropping any descendants orphaned by the eviction.
+    fn prune_stale(&mut self, now: Instant) {
+        // Only evict roots of a pending chain (blocks whose own predecessor
+        // isn't itself pending); their descendants are dropped as orphans by
+        // `evict_pending` regardless of whether they have individually
+        // crossed the TTL, so the whole waiting subtree ages out together.
+        let stale_roots: Vec<String> = self
+            .pending_metadata
+            .iter()
+            .filter(|(_, meta)| now.saturating_duration_since(meta.first_seen) >= self.pending_ttl)
+            .filter(|(_, meta)| !self.pending_metadata.contains_key(&meta.previous_block_id))
+            .map(|(block_id, _)| block_id.clone())
+            .collect();
+
+        for block_id in stale_roots {
+            self.evict_pending(&block_id);
+        }
+
+        self.update_gauges();
+    }
+
+    fn evict_pending(&mut self, block_id: &str) {
+        let meta = match self.pending_metadata.remove(block_id) {
+            Some(meta) => meta,
+            None => return,
+        };
+        self.pending.remove(block_id);
+
+        let evicted_block = self
+            .descendants_by_previous_id
+            .get_mut(&meta.previous_block_id)
+            .and_then(|siblings| {
+                let position = siblings
+                    .iter()
+                    .position(|sibling| sibling.header_signature == block_id)?;
+                Some(siblings.remove(position))
+            });
+
+        if self
+            .descendants_by_previous_id
+            .get(&meta.previous_block_id)
+            .map_or(false, |siblings| siblings.is_empty())
+        {
+            self.descendants_by_previous_id.remove(&meta.previous_block_id);
+        }
+
+        if let Some(block) = evicted_block {
+            info!(
+                "Pending block {} exceeded the predecessor wait threshold after {} attempt(s), evicting",
+                block_id, meta.attempts
+            );
+            if let Some(sender) = &self.needs_predecessor_sender {
+                let _ = sender.send(block);
+            }
+        }
+
+        self.drop_orphaned_descendants(block_id);
+    }
+
+    /// Remove descendants of an evicted block from `pending` bookkeeping,
+    /// since the predecessor they were waiting on no longer exists.
+    fn drop_orphaned_descendants(&mut self, block_id: &str) {
+        if let Some(orphans) = self.descendants_by_previous_id.remove(block_id) {
+            for orphan in orphans {
+                self.pending.remove(&orphan.header_signature);
+                self.pending_metadata.remove(&orphan.header_signature);
+                self.drop_orphaned_descendants(&orphan.header_signature);
+            }
+        }
+    }
+
+    fn has_capacity(&self) -> bool {
+        match self.max_in_flight {
+            Some(limit) => self.processing.len() < limit,
+            None => true,
+        }
+    }
+
+    /// Admit `block` into `processing` and `ready` if a slot is available
+    /// under `max_in_flight`; otherwise park it in the ordered admission
+    /// queue to be released by `done` as slots free up.
+    fn admit(&mut self, block: Block, ready: &mut Vec<Block>) {
+        if self.has_capacity() {
+            self.processing.insert(block.header_signature.clone());
+            ready.push(block);
+        } else {
+            debug!(
+                "Processing at max_in_flight capacity, queuing block {} for admission",
+                &block.header_signature
+            );
+            self.admission_queue.push_back(block);
+        }
+    }
+
+    /// Release queued blocks into `processing` while slots are free.
+    fn release_admitted(&mut self, ready: &mut Vec<Block>) {
+        while self.has_capacity() {
+            match self.admission_queue.pop_front() {
+                Some(block) => {
+                    self.processing.insert(block.header_signature.clone());
+                    ready.push(block);
+                }
+                None => break,
+            }
+        }
+    }
+
     fn update_gauges(&self) {
         let mut blocks_processing = COLLECTOR.gauge("BlockScheduler.blocks_processing", None, None);
         blocks_processing.set_value(self.processing.len());
         let mut blocks_pending = COLLECTOR.gauge("BlockScheduler.blocks_pending", None, None);
-        blocks_pending.set_value(self.pending.len())
+        blocks_pending.set_value(self.pending.len());
+        let mut blocks_queued = COLLECTOR.gauge("BlockScheduler.blocks_queued", None, None);
+        blocks_queued.set_value(self.admission_queue.len())
     }
 }
 
@@ -512,6 +1029,345 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_validation_stage_persists_across_reschedule() {
+        let block_manager = BlockManager::new();
+        let block_status_store = MockStore::new();
+        let block_a = create_block("A", NULL_BLOCK_IDENTIFIER, 0);
+
+        let block_scheduler = BlockScheduler::new(block_manager, block_status_store);
+
+        assert_eq!(
+            block_scheduler.validation_stage(&block_a.header_signature),
+            ValidationStage::Unchecked
+        );
+
+        // Progress can only be recorded by actually constructing the
+        // typestate wrapper for a stage, which can only be built from the
+        // prior stage -- `SignatureVerified::new` takes a `RelevancyChecked`
+        // by value, so there is no way to call `mark_signature_verified`
+        // without having gone through `mark_relevancy_checked` first.
+        let checked = RelevancyChecked::new(block_a.clone());
+        block_scheduler.mark_relevancy_checked(&checked);
+        assert_eq!(
+            block_scheduler.validation_stage(&block_a.header_signature),
+            ValidationStage::Relevancy
+        );
+
+        let verified = SignatureVerified::new(checked);
+        block_scheduler.mark_signature_verified(&verified);
+        assert_eq!(
+            block_scheduler.validation_stage(&block_a.header_signature),
+            ValidationStage::SignatureVerified
+        );
+
+        // Advancing to an earlier stage must not regress the recorded progress.
+        let regressed = RelevancyChecked::new(block_a.clone());
+        block_scheduler.mark_relevancy_checked(&regressed);
+        assert_eq!(
+            block_scheduler.validation_stage(&block_a.header_signature),
+            ValidationStage::SignatureVerified
+        );
+
+        let pending = ExecutionPending::new(verified);
+        block_scheduler.mark_execution_pending(&pending);
+        assert_eq!(
+            block_scheduler.validation_stage(&block_a.header_signature),
+            ValidationStage::ExecutionPending
+        );
+
+        // Once validation completes, the recorded stage is cleared.
+        block_scheduler.schedule(vec![block_a.clone()]);
+        block_scheduler.done(&block_a.header_signature, false);
+        assert_eq!(
+            block_scheduler.validation_stage(&block_a.header_signature),
+            ValidationStage::Unchecked
+        );
+    }
+
+    #[test]
+    fn test_schedule_segment_admits_whole_contiguous_chain() {
+        let block_manager = BlockManager::new();
+        let block_status_store = MockStore::new();
+        let block_a = create_block("A", NULL_BLOCK_IDENTIFIER, 0);
+        let block_b = create_block("B", "A", 1);
+        let block_c = create_block("C", "B", 2);
+
+        let block_scheduler = BlockScheduler::new(block_manager, block_status_store);
+
+        assert_eq!(
+            block_scheduler.schedule_segment(vec![
+                block_a.clone(),
+                block_b.clone(),
+                block_c.clone()
+            ]),
+            vec![block_a, block_b, block_c],
+            "A contiguous segment rooted at genesis is admitted in one pass"
+        );
+    }
+
+    #[test]
+    fn test_schedule_segment_rejects_non_contiguous_chain() {
+        let block_manager = BlockManager::new();
+        let block_status_store = MockStore::new();
+        let block_a = create_block("A", NULL_BLOCK_IDENTIFIER, 0);
+        let block_c = create_block("C", "B", 2); // "B" is missing from the segment
+
+        let block_scheduler = BlockScheduler::new(block_manager, block_status_store);
+
+        assert_eq!(
+            block_scheduler.schedule_segment(vec![block_a, block_c]),
+            vec![],
+            "A segment whose links do not form a single path is rejected outright"
+        );
+    }
+
+    #[test]
+    fn test_schedule_segment_rejects_gap_opened_by_dedup() {
+        let block_manager = BlockManager::new();
+        let block_status_store = MockStore::new();
+        let block_a = create_block("A", NULL_BLOCK_IDENTIFIER, 0);
+        let block_b = create_block("B", "A", 1);
+        let block_c = create_block("C", "B", 2);
+
+        let block_scheduler = BlockScheduler::new(block_manager, block_status_store);
+
+        // "B" is already known to the scheduler via some other path (e.g. a
+        // sibling segment), with no bearing on this segment's A->B->C chain.
+        let already_known_b = create_block("B", NULL_BLOCK_IDENTIFIER, 1);
+        assert_eq!(
+            block_scheduler.schedule(vec![already_known_b]),
+            vec![create_block("B", NULL_BLOCK_IDENTIFIER, 1)]
+        );
+
+        // Deduping "B" out of this segment leaves [A, C], which is no longer
+        // contiguous: C's real predecessor (B) was filtered out without its
+        // status ever being resolved against this chain, so the whole
+        // segment must be rejected rather than silently admitting C.
+        assert_eq!(
+            block_scheduler.schedule_segment(vec![block_a, block_b, block_c]),
+            vec![],
+            "A gap opened by dedup must reject the segment, not admit past it"
+        );
+    }
+
+    #[test]
+    fn test_schedule_segment_parks_segment_with_unknown_anchor() {
+        let block_manager = BlockManager::new();
+        let block_status_store = MockStore::new();
+        let block_b = create_block("B", "UNKNOWN", 1);
+        let block_c = create_block("C", "B", 2);
+
+        let block_scheduler = BlockScheduler::new(block_manager, block_status_store);
+
+        assert_eq!(
+            block_scheduler.schedule_segment(vec![block_b, block_c]),
+            vec![],
+            "Nothing is ready while the segment's ancestor is unresolved"
+        );
+    }
+
+    #[test]
+    fn test_fork_preference_orders_siblings_by_block_num_then_signature() {
+        let block_manager = BlockManager::new();
+        let block_status_store = MockStore::new();
+        let block_a = create_block("A", NULL_BLOCK_IDENTIFIER, 0);
+        let block_b1 = create_block("B1", "A", 1);
+        let block_b2 = create_block("B2", "A", 1);
+        let block_c = create_block("C", "A", 2);
+
+        block_manager
+            .put(vec![block_a.clone()])
+            .expect("The block manager failed to `put` a branch");
+
+        let block_scheduler = BlockScheduler::new(block_manager, block_status_store);
+
+        block_scheduler.schedule(vec![block_a.clone()]);
+
+        assert_eq!(
+            block_scheduler.done(&block_a.header_signature, false),
+            vec![]
+        );
+
+        assert_eq!(
+            block_scheduler.schedule(vec![block_b1.clone(), block_c.clone(), block_b2.clone()]),
+            vec![block_c, block_b1, block_b2],
+            "The block with the greatest block_num is validated first, ties broken by signature"
+        );
+    }
+
+    #[test]
+    fn test_prune_stale_evicts_expired_pending_blocks_and_orphans() {
+        let block_manager = BlockManager::new();
+        let block_status_store = MockStore::new();
+        let block_root = create_block("ROOT", NULL_BLOCK_IDENTIFIER, 0);
+        let block_unknown = create_block("UNKNOWN", "ROOT", 1);
+        let block_a1 = create_block("A1", "UNKNOWN", 2);
+        let block_a2 = create_block("A2", "A1", 3);
+
+        block_manager
+            .put(vec![block_root.clone(), block_unknown.clone()])
+            .expect("The block manager failed to `put` a branch");
+
+        let block_scheduler = BlockScheduler::new(block_manager, block_status_store);
+        block_scheduler.set_pending_ttl(Duration::from_secs(0));
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        block_scheduler.set_needs_predecessor_sender(sender);
+
+        // A1's predecessor is unknown; scheduling it parks A1 in pending and,
+        // as a side effect of walking the unknown predecessor's ancestry,
+        // admits the UNKNOWN block itself for processing.
+        assert_eq!(
+            block_scheduler.schedule(vec![block_a1.clone()]),
+            vec![block_unknown.clone()]
+        );
+        assert!(block_scheduler.contains(&block_a1.header_signature));
+
+        // A2 is waiting on A1, which is still pending on UNKNOWN being done.
+        assert_eq!(block_scheduler.schedule(vec![block_a2.clone()]), vec![]);
+        assert!(block_scheduler.contains(&block_a2.header_signature));
+
+        block_scheduler.prune_stale(Instant::now());
+
+        assert!(!block_scheduler.contains(&block_a1.header_signature));
+        assert!(
+            !block_scheduler.contains(&block_a2.header_signature),
+            "A2 is dropped as an orphaned descendant of the evicted A1"
+        );
+        assert_eq!(receiver.try_recv(), Ok(block_a1));
+        assert!(
+            receiver.try_recv().is_err(),
+            "Orphaned descendants are dropped, not individually re-emitted"
+        );
+    }
+
+    #[test]
+    fn test_max_in_flight_queues_excess_and_releases_on_done() {
+        let block_manager = BlockManager::new();
+        let block_status_store = MockStore::new();
+        let block_a = create_block("A", NULL_BLOCK_IDENTIFIER, 0);
+        let block_b1 = create_block("B1", "A", 1);
+        let block_b2 = create_block("B2", "A", 1);
+
+        let block_scheduler = BlockScheduler::new(block_manager, block_status_store);
+        block_scheduler.set_max_in_flight(Some(1));
+
+        assert_eq!(
+            block_scheduler.schedule(vec![block_a.clone()]),
+            vec![block_a.clone()],
+            "The single slot is taken by A"
+        );
+
+        assert_eq!(
+            block_scheduler.done(&block_a.header_signature, false),
+            vec![],
+            "A has no descendants yet; finishing it frees the only slot"
+        );
+
+        assert_eq!(
+            block_scheduler.schedule(vec![block_b1.clone(), block_b2.clone()]),
+            vec![block_b1.clone()],
+            "Only one of B1/B2 is admitted into the single slot; the other is queued"
+        );
+
+        assert_eq!(
+            block_scheduler.done(&block_b1.header_signature, false),
+            vec![block_b2],
+            "Finishing B1 frees the slot, releasing the queued B2"
+        );
+    }
+
+    #[test]
+    fn test_schedule_segment_invalidates_suffix_block_past_capacity() {
+        let block_manager = BlockManager::new();
+        let block_status_store: Arc<Mutex<HashMap<String, BlockStatus>>> =
+            Arc::new(Mutex::new(HashMap::new()));
+        let block_a = create_block("A", NULL_BLOCK_IDENTIFIER, 0);
+        let block_b = create_block("B", "A", 1);
+        let block_c = create_block("C", "B", 2);
+
+        block_status_store
+            .lock()
+            .expect("Mutex was poisoned")
+            .insert(block_c.header_signature.clone(), BlockStatus::Invalid);
+
+        let block_scheduler = BlockScheduler::new(block_manager, block_status_store);
+        block_scheduler.set_max_in_flight(Some(1));
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        block_scheduler.set_results_sender(sender);
+
+        // Capacity runs out after A, so B and C are never individually
+        // checked against block_status_store by the forward scan; without
+        // the fix both would be queued for later admission even though C is
+        // already known invalid, letting it slip past invalidation.
+        assert_eq!(
+            block_scheduler.schedule_segment(vec![block_a.clone(), block_b.clone(), block_c.clone()]),
+            vec![block_a.clone()],
+            "Only A fits in the single in-flight slot"
+        );
+
+        let result = receiver
+            .try_recv()
+            .expect("C is invalidated immediately rather than queued");
+        assert_eq!(result.block_id, block_c.header_signature);
+        assert_eq!(result.status, BlockStatus::Invalid);
+
+        assert_eq!(
+            block_scheduler.done(&block_a.header_signature, false),
+            vec![],
+            "The invalidated C stays in processing until its own `done`, so finishing A frees no slot"
+        );
+
+        assert_eq!(
+            block_scheduler.done(&block_c.header_signature, true),
+            vec![block_b],
+            "Finishing C frees the slot, releasing the queued B; C was never queued"
+        );
+    }
+
+    #[test]
+    fn test_done_respects_max_in_flight_when_releasing_descendants() {
+        let block_manager = BlockManager::new();
+        let block_status_store = MockStore::new();
+        let block_a = create_block("A", NULL_BLOCK_IDENTIFIER, 0);
+        let block_b1 = create_block("B1", "A", 1);
+        let block_b2 = create_block("B2", "A", 1);
+
+        let block_scheduler = BlockScheduler::new(block_manager, block_status_store);
+        block_scheduler.set_max_in_flight(Some(1));
+
+        assert_eq!(
+            block_scheduler.schedule(vec![block_a.clone()]),
+            vec![block_a.clone()],
+            "The single slot is taken by A"
+        );
+
+        // A is still in processing, so B1/B2 are parked in pending as
+        // descendants rather than admitted directly.
+        assert_eq!(
+            block_scheduler.schedule(vec![block_b1.clone(), block_b2.clone()]),
+            vec![],
+            "B1/B2 wait in pending while A is still processing"
+        );
+
+        // Finishing A frees up both descendants at once; `done` must admit
+        // only as many as `max_in_flight` allows rather than dumping all of
+        // them into `processing` unconditionally.
+        assert_eq!(
+            block_scheduler.done(&block_a.header_signature, false),
+            vec![block_b1.clone()],
+            "Only B1 is admitted into the freed slot; B2 stays queued"
+        );
+
+        assert_eq!(
+            block_scheduler.done(&block_b1.header_signature, false),
+            vec![block_b2],
+            "Finishing B1 releases the still-queued B2"
+        );
+    }
+
     fn create_block(header_signature: &str, previous_block_id: &str, block_num: u64) -> Block {
         Block {
             header_signature: header_signature.into(),
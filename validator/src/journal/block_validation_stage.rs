@@ -0,0 +1,129 @@
+/*
+ * Copyright 2018 Intel Corporation
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * ------------------------------------------------------------------------------
+ */
+
+//! Typestate wrappers around [`Block`] that encode how far a block has
+//! progressed through incremental validation. Each stage can only be
+//! constructed from the prior stage, so the compiler enforces that no
+//! downstream consumer skips a check: you cannot obtain an
+//! `ExecutionPending` without first having passed through
+//! `RelevancyChecked` and `SignatureVerified`. `BlockScheduler::mark_*`
+//! (see block_scheduler.rs) only accepts these wrapper types, not a bare
+//! `ValidationStage`, so recording progress out of order is a compile error,
+//! not a runtime one.
+
+use crate::block::Block;
+
+/// The predecessor has been resolved to a known block and the block is
+/// confirmed not to be a duplicate of one already pending or processing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RelevancyChecked(Block);
+
+impl RelevancyChecked {
+    /// Wrap `block` once its predecessor has been resolved and it has been
+    /// confirmed not to be a duplicate of a block already known to the
+    /// scheduler.
+    pub fn new(block: Block) -> Self {
+        RelevancyChecked(block)
+    }
+
+    pub fn block(&self) -> &Block {
+        &self.0
+    }
+
+    pub fn into_block(self) -> Block {
+        self.0
+    }
+}
+
+/// Batch header, individual batch signatures, and the block header signature
+/// have all been validated.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SignatureVerified(Block);
+
+impl SignatureVerified {
+    /// Promote a `RelevancyChecked` block once its signatures have been
+    /// verified. Only constructible from the prior stage.
+    pub fn new(checked: RelevancyChecked) -> Self {
+        SignatureVerified(checked.0)
+    }
+
+    pub fn block(&self) -> &Block {
+        &self.0
+    }
+
+    pub fn into_block(self) -> Block {
+        self.0
+    }
+}
+
+/// The block is ready to be handed to the executor for state transition.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExecutionPending(Block);
+
+impl ExecutionPending {
+    /// Promote a `SignatureVerified` block once it is ready for execution.
+    /// Only constructible from the prior stage.
+    pub fn new(verified: SignatureVerified) -> Self {
+        ExecutionPending(verified.0)
+    }
+
+    pub fn block(&self) -> &Block {
+        &self.0
+    }
+
+    pub fn into_block(self) -> Block {
+        self.0
+    }
+}
+
+/// The highest validation stage a block has reached. `BlockSchedulerState`
+/// persists one of these per block so that a block which re-enters
+/// scheduling (cache miss, fork re-org) resumes from here instead of
+/// re-validating signatures and hashes that already passed.
+///
+/// Variants are declared in validation order so that `Ord` reflects
+/// progress: a later variant is always further along than an earlier one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ValidationStage {
+    /// No validation has been performed yet.
+    Unchecked,
+    /// See [`RelevancyChecked`].
+    Relevancy,
+    /// See [`SignatureVerified`].
+    SignatureVerified,
+    /// See [`ExecutionPending`].
+    ExecutionPending,
+}
+
+impl Default for ValidationStage {
+    fn default() -> Self {
+        ValidationStage::Unchecked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validation_stage_ordering_is_monotonic() {
+        assert!(ValidationStage::Unchecked < ValidationStage::Relevancy);
+        assert!(ValidationStage::Relevancy < ValidationStage::SignatureVerified);
+        assert!(ValidationStage::SignatureVerified < ValidationStage::ExecutionPending);
+        assert_eq!(ValidationStage::default(), ValidationStage::Unchecked);
+    }
+}
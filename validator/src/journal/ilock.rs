@@ -1,31 +1,81 @@
+//! An instrumented `RwLock` that traces acquisition and release to help
+//! diagnose contention and deadlocks. The verbose per-acquisition tracing —
+//! string formatting, the `Wait`/`Acq`/`Rel`/`Try`/`NoAcq` log lines, and the
+//! `place` string kept per guard — is gated behind the `lock-trace` feature;
+//! with the feature off, `read` and `write` collapse to thin wrappers over
+//! [`std::sync::RwLock`] so the abstraction is free to use unconditionally,
+//! including in hot paths. The slow-hold warning (see
+//! `IRwLock::new_with_slow_hold_threshold`) is a separate, always-on check:
+//! it is cheap enough to run in production and would defeat its own purpose
+//! if it only fired in `lock-trace` builds.
+
+use log::warn;
+#[cfg(feature = "lock-trace")]
 use log::error;
 use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::PoisonError;
 use std::sync::RwLock;
 use std::sync::{RwLockReadGuard, RwLockWriteGuard};
+use std::sync::{TryLockError, TryLockResult};
 use std::thread::current;
+use std::time::{Duration, Instant};
+
+#[cfg(debug_assertions)]
+use std::collections::HashSet;
+#[cfg(debug_assertions)]
+use std::sync::Mutex as StdMutex;
+#[cfg(debug_assertions)]
+use std::thread::ThreadId;
+
+/// Tracks which thread(s) currently hold an `IRwLock`, so that a thread
+/// re-acquiring a lock it already holds can be caught and panicked on
+/// instead of silently deadlocking. Debug-only: the bookkeeping is pure
+/// overhead in release builds, which rely on the caller not doing this.
+#[cfg(debug_assertions)]
+#[derive(Default)]
+struct LockOwners {
+    writer: StdMutex<Option<ThreadId>>,
+    readers: StdMutex<HashSet<ThreadId>>,
+}
 
 #[derive(Default)]
 pub struct IRwLock<T> {
     inner: RwLock<T>,
     name: String,
+    #[cfg(debug_assertions)]
+    owners: LockOwners,
+    /// If a guard is held at least this long, its release is additionally
+    /// logged as a slow hold. `None` (the default) disables the check. This
+    /// check is cheap (one `Instant::elapsed` and a `Duration` compare) and
+    /// runs regardless of the `lock-trace` feature, unlike the verbose
+    /// per-acquisition string tracing that feature gates.
+    slow_hold_threshold: Option<Duration>,
+    /// When true, `read`/`write` transparently recover a poisoned lock
+    /// instead of propagating `PoisonError`. Defaults to false so poisoning
+    /// behaves as it always has.
+    recover_from_poison: bool,
+    /// Ensures the one-time `Poisoned` trace event fires only once.
+    poison_logged: AtomicBool,
 }
 
 pub struct IRwLockReadGuard<'a, T> {
     inner: RwLockReadGuard<'a, T>,
     lock: &'a IRwLock<T>,
+    #[cfg(feature = "lock-trace")]
     place: String,
+    acquired_at: Instant,
 }
 
 impl<T> Drop for IRwLockReadGuard<'_, T> {
     fn drop(&mut self) {
-        error!(
-            "θ;{};Rel;{};{};{}",
-            self.lock.name,
-            'R',
-            self.lock.thread(),
-            self.place
-        );
+        #[cfg(debug_assertions)]
+        self.lock.unregister_reader();
+
+        self.lock.check_slow_hold('R', self.acquired_at);
+
+        #[cfg(feature = "lock-trace")]
+        self.lock.log_release('R', &self.place, self.acquired_at);
     }
 }
 
@@ -37,10 +87,46 @@ impl<T> Deref for IRwLockReadGuard<'_, T> {
     }
 }
 
+impl<'a, T> IRwLockReadGuard<'a, T> {
+    /// Project this guard to a narrower view of the locked data via `f`,
+    /// without releasing the lock. The returned guard still emits the `Rel`
+    /// trace event (with the original `name`/`place`) when it drops.
+    pub fn map<U, F>(orig: Self, f: F) -> IMappedRwLockReadGuard<'a, T, U>
+    where
+        F: FnOnce(&T) -> &U,
+    {
+        let projected: *const U = f(&orig);
+        IMappedRwLockReadGuard {
+            _inner: orig,
+            projected,
+        }
+    }
+}
+
+/// A read guard projected to a sub-field of the locked data via
+/// `IRwLockReadGuard::map`. Holds the original guard so the lock stays held,
+/// and its original `name`/`place` are still traced on drop.
+pub struct IMappedRwLockReadGuard<'a, T, U> {
+    _inner: IRwLockReadGuard<'a, T>,
+    projected: *const U,
+}
+
+impl<T, U> Deref for IMappedRwLockReadGuard<'_, T, U> {
+    type Target = U;
+
+    fn deref(&self) -> &Self::Target {
+        // Safe because `projected` points into the data behind the
+        // `RwLock`, which `_inner` keeps locked and alive for `'a`.
+        unsafe { &*self.projected }
+    }
+}
+
 pub struct IRwLockWriteGuard<'a, T> {
     inner: RwLockWriteGuard<'a, T>,
     lock: &'a IRwLock<T>,
+    #[cfg(feature = "lock-trace")]
     place: String,
+    acquired_at: Instant,
 }
 
 impl<T> DerefMut for IRwLockWriteGuard<'_, T> {
@@ -51,13 +137,13 @@ impl<T> DerefMut for IRwLockWriteGuard<'_, T> {
 
 impl<'a, T> Drop for IRwLockWriteGuard<'a, T> {
     fn drop(&mut self) {
-        error!(
-            "θ;{};Rel;{};{};{}",
-            self.lock.name,
-            'W',
-            self.lock.thread(),
-            self.place
-        );
+        #[cfg(debug_assertions)]
+        self.lock.unregister_writer();
+
+        self.lock.check_slow_hold('W', self.acquired_at);
+
+        #[cfg(feature = "lock-trace")]
+        self.lock.log_release('W', &self.place, self.acquired_at);
     }
 }
 
@@ -69,14 +155,137 @@ impl<T> Deref for IRwLockWriteGuard<'_, T> {
     }
 }
 
+impl<'a, T> IRwLockWriteGuard<'a, T> {
+    /// Project this guard to a narrower, mutable view of the locked data via
+    /// `f`, without releasing the lock. The returned guard still emits the
+    /// `Rel` trace event (with the original `name`/`place`) when it drops.
+    pub fn map<U, F>(mut orig: Self, f: F) -> IMappedRwLockWriteGuard<'a, T, U>
+    where
+        F: FnOnce(&mut T) -> &mut U,
+    {
+        let projected: *mut U = f(&mut orig);
+        IMappedRwLockWriteGuard {
+            _inner: orig,
+            projected,
+        }
+    }
+}
+
+/// A write guard projected to a sub-field of the locked data via
+/// `IRwLockWriteGuard::map`. Holds the original guard so the lock stays
+/// held, and its original `name`/`place` are still traced on drop.
+pub struct IMappedRwLockWriteGuard<'a, T, U> {
+    _inner: IRwLockWriteGuard<'a, T>,
+    projected: *mut U,
+}
+
+impl<T, U> Deref for IMappedRwLockWriteGuard<'_, T, U> {
+    type Target = U;
+
+    fn deref(&self) -> &Self::Target {
+        // Safe because `projected` points into the data behind the
+        // `RwLock`, which `_inner` keeps locked and alive for `'a`.
+        unsafe { &*self.projected }
+    }
+}
+
+impl<T, U> DerefMut for IMappedRwLockWriteGuard<'_, T, U> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // Safe for the same reason as `Deref::deref` above; `_inner` gives
+        // us exclusive access to the locked data for `'a`.
+        unsafe { &mut *self.projected }
+    }
+}
+
 impl<T> IRwLock<T> {
     pub fn new(name: String, t: T) -> IRwLock<T> {
         IRwLock {
             inner: RwLock::new(t),
             name,
+            #[cfg(debug_assertions)]
+            owners: LockOwners::default(),
+            slow_hold_threshold: None,
+            recover_from_poison: false,
+            poison_logged: AtomicBool::new(false),
         }
     }
 
+    /// Like `new`, but logs a slow-hold warning if a guard is ever held for
+    /// at least `slow_hold_threshold`, so excessively long critical sections
+    /// surface automatically instead of only being visible as a duration in
+    /// the trace.
+    pub fn new_with_slow_hold_threshold(
+        name: String,
+        t: T,
+        slow_hold_threshold: Duration,
+    ) -> IRwLock<T> {
+        IRwLock {
+            inner: RwLock::new(t),
+            name,
+            #[cfg(debug_assertions)]
+            owners: LockOwners::default(),
+            slow_hold_threshold: Some(slow_hold_threshold),
+            recover_from_poison: false,
+            poison_logged: AtomicBool::new(false),
+        }
+    }
+
+    /// Like `new`, but a panic while holding this lock does not permanently
+    /// poison it: `read`/`write` transparently recover the guard out of the
+    /// `PoisonError` and return `Ok`, logging a one-time `Poisoned` trace
+    /// event so the recovery is still auditable.
+    pub fn new_unpoisonable(name: String, t: T) -> IRwLock<T> {
+        IRwLock {
+            inner: RwLock::new(t),
+            name,
+            #[cfg(debug_assertions)]
+            owners: LockOwners::default(),
+            slow_hold_threshold: None,
+            recover_from_poison: true,
+            poison_logged: AtomicBool::new(false),
+        }
+    }
+
+    fn log_poison_once(&self) {
+        if self.poison_logged.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        warn!("θ;{};Poisoned;{};recovered", self.name, self.thread());
+    }
+
+    /// Log a warning if the guard being released was held for at least
+    /// `slow_hold_threshold`. Kept separate from `log_release` below and
+    /// always compiled in: the threshold compare is cheap, so unlike the
+    /// verbose per-acquisition trace it shouldn't only run in `lock-trace`
+    /// builds.
+    fn check_slow_hold(&self, mode: char, acquired_at: Instant) {
+        if let Some(threshold) = self.slow_hold_threshold {
+            let held = acquired_at.elapsed();
+            if held >= threshold {
+                warn!(
+                    "θ;{};SlowRelease;{};{};held={:?};threshold={:?}",
+                    self.name,
+                    mode,
+                    self.thread(),
+                    held,
+                    threshold
+                );
+            }
+        }
+    }
+
+    #[cfg(feature = "lock-trace")]
+    fn log_release(&self, mode: char, place: &str, acquired_at: Instant) {
+        error!(
+            "θ;{};Rel;{};{};{};held={:?}",
+            self.name,
+            mode,
+            self.thread(),
+            place,
+            acquired_at.elapsed()
+        );
+    }
+
     fn thread(&self) -> String {
         current()
             .name()
@@ -84,33 +293,329 @@ impl<T> IRwLock<T> {
             .unwrap_or("<unknown>".into())
     }
 
+    /// Panic if the current thread already holds this lock in a way that
+    /// would deadlock it acquiring `mode` ('R' or 'W'): the current thread
+    /// already holds the write lock, or (for a write request) already holds
+    /// the read lock.
+    #[cfg(debug_assertions)]
+    fn check_current_thread(&self, mode: char, place: &str) {
+        let thread_id = current().id();
+
+        let holds_write = *self
+            .owners
+            .writer
+            .lock()
+            .expect("IRwLock owner-tracking mutex was poisoned")
+            == Some(thread_id);
+
+        let holds_read = mode == 'W'
+            && self
+                .owners
+                .readers
+                .lock()
+                .expect("IRwLock owner-tracking mutex was poisoned")
+                .contains(&thread_id);
+
+        if holds_write || holds_read {
+            panic!(
+                "deadlock detected, lock '{}' already acquired in the current thread at '{}'",
+                self.name, place
+            );
+        }
+    }
+
+    #[cfg(debug_assertions)]
+    fn register_reader(&self) {
+        self.owners
+            .readers
+            .lock()
+            .expect("IRwLock owner-tracking mutex was poisoned")
+            .insert(current().id());
+    }
+
+    #[cfg(debug_assertions)]
+    fn unregister_reader(&self) {
+        self.owners
+            .readers
+            .lock()
+            .expect("IRwLock owner-tracking mutex was poisoned")
+            .remove(&current().id());
+    }
+
+    #[cfg(debug_assertions)]
+    fn register_writer(&self) {
+        *self
+            .owners
+            .writer
+            .lock()
+            .expect("IRwLock owner-tracking mutex was poisoned") = Some(current().id());
+    }
+
+    #[cfg(debug_assertions)]
+    fn unregister_writer(&self) {
+        *self
+            .owners
+            .writer
+            .lock()
+            .expect("IRwLock owner-tracking mutex was poisoned") = None;
+    }
+
+    /// Acquire the read lock at `place` (a short human-readable description
+    /// of the call site, used for tracing and, in debug builds, in the
+    /// deadlock-detection panic message). With the `lock-trace` feature
+    /// disabled, `place` is never copied or formatted except in that panic,
+    /// and this is a thin wrapper over `std::sync::RwLock::read`.
     pub fn read(
         &self,
         place: &str,
     ) -> Result<IRwLockReadGuard<T>, PoisonError<RwLockReadGuard<T>>> {
+        #[cfg(debug_assertions)]
+        self.check_current_thread('R', place);
+        #[cfg(not(feature = "lock-trace"))]
+        let _ = place;
+
+        #[cfg(feature = "lock-trace")]
+        let wait_start = Instant::now();
+        #[cfg(feature = "lock-trace")]
         error!("θ;{};Wait;{};{};{}", self.name, 'R', self.thread(), place);
-        let inner = self.inner.read()?;
-        error!("θ;{};Acq;{};{};{}", self.name, 'R', self.thread(), place);
+        let inner = match self.inner.read() {
+            Ok(inner) => inner,
+            Err(err) if self.recover_from_poison => {
+                self.log_poison_once();
+                err.into_inner()
+            }
+            Err(err) => return Err(err),
+        };
+        #[cfg(debug_assertions)]
+        self.register_reader();
+        let acquired_at = Instant::now();
+        #[cfg(feature = "lock-trace")]
+        error!(
+            "θ;{};Acq;{};{};{};wait={:?}",
+            self.name,
+            'R',
+            self.thread(),
+            place,
+            acquired_at.duration_since(wait_start)
+        );
         Ok(IRwLockReadGuard {
             inner,
             lock: self,
+            #[cfg(feature = "lock-trace")]
             place: place.to_string(),
+            acquired_at,
         })
     }
 
+    /// Acquire the write lock at `place` (a short human-readable description
+    /// of the call site, used for tracing and, in debug builds, in the
+    /// deadlock-detection panic message). With the `lock-trace` feature
+    /// disabled, `place` is never copied or formatted except in that panic,
+    /// and this is a thin wrapper over `std::sync::RwLock::write`.
     pub fn write(
         &self,
         place: &str,
     ) -> Result<IRwLockWriteGuard<T>, PoisonError<RwLockWriteGuard<T>>> {
+        #[cfg(debug_assertions)]
+        self.check_current_thread('W', place);
+        #[cfg(not(feature = "lock-trace"))]
+        let _ = place;
+
+        #[cfg(feature = "lock-trace")]
+        let wait_start = Instant::now();
+        #[cfg(feature = "lock-trace")]
         error!("θ;{};Wait;{};{};{}", self.name, 'W', self.thread(), place);
-        //newtype guard
-        let inner = self.inner.write()?;
-        error!("θ;{};Acq;{};{};{}", self.name, 'W', self.thread(), place);
+        let inner = match self.inner.write() {
+            Ok(inner) => inner,
+            Err(err) if self.recover_from_poison => {
+                self.log_poison_once();
+                err.into_inner()
+            }
+            Err(err) => return Err(err),
+        };
+        #[cfg(debug_assertions)]
+        self.register_writer();
+        let acquired_at = Instant::now();
+        #[cfg(feature = "lock-trace")]
+        error!(
+            "θ;{};Acq;{};{};{};wait={:?}",
+            self.name,
+            'W',
+            self.thread(),
+            place,
+            acquired_at.duration_since(wait_start)
+        );
         Ok(IRwLockWriteGuard {
             inner,
             lock: self,
+            #[cfg(feature = "lock-trace")]
             place: place.to_string(),
+            acquired_at,
+        })
+    }
+
+    /// Like `read`, but returns immediately instead of blocking when the
+    /// lock is busy, so contention that would otherwise be invisible shows
+    /// up in the trace as a `NoAcq` rather than never emitting `Wait`/`Acq`.
+    pub fn try_read(&self, place: &str) -> TryLockResult<IRwLockReadGuard<T>> {
+        // No same-thread reentrancy check here: try_read never blocks, so it
+        // cannot deadlock against a read/write guard already held on this
+        // thread. Calling check_current_thread here would wrongly panic when
+        // a thread holding the write guard opportunistically calls try_read,
+        // instead of returning Err(WouldBlock) as the caller expects.
+        #[cfg(not(feature = "lock-trace"))]
+        let _ = place;
+
+        #[cfg(feature = "lock-trace")]
+        error!("θ;{};Try;{};{};{}", self.name, 'R', self.thread(), place);
+        match self.inner.try_read() {
+            Ok(inner) => {
+                #[cfg(debug_assertions)]
+                self.register_reader();
+                #[cfg(feature = "lock-trace")]
+                error!("θ;{};Acq;{};{};{}", self.name, 'R', self.thread(), place);
+                Ok(IRwLockReadGuard {
+                    inner,
+                    lock: self,
+                    #[cfg(feature = "lock-trace")]
+                    place: place.to_string(),
+                    acquired_at: Instant::now(),
+                })
+            }
+            Err(TryLockError::WouldBlock) => {
+                #[cfg(feature = "lock-trace")]
+                error!("θ;{};NoAcq;{};{};{}", self.name, 'R', self.thread(), place);
+                Err(TryLockError::WouldBlock)
+            }
+            Err(TryLockError::Poisoned(err)) => {
+                #[cfg(debug_assertions)]
+                self.register_reader();
+                let guard = IRwLockReadGuard {
+                    inner: err.into_inner(),
+                    lock: self,
+                    #[cfg(feature = "lock-trace")]
+                    place: place.to_string(),
+                    acquired_at: Instant::now(),
+                };
+                if self.recover_from_poison {
+                    self.log_poison_once();
+                    #[cfg(feature = "lock-trace")]
+                    error!("θ;{};Acq;{};{};{}", self.name, 'R', self.thread(), place);
+                    Ok(guard)
+                } else {
+                    #[cfg(feature = "lock-trace")]
+                    error!("θ;{};NoAcq;{};{};{}", self.name, 'R', self.thread(), place);
+                    Err(TryLockError::Poisoned(PoisonError::new(guard)))
+                }
+            }
+        }
+    }
+
+    /// Like `write`, but returns immediately instead of blocking when the
+    /// lock is busy, so contention that would otherwise be invisible shows
+    /// up in the trace as a `NoAcq` rather than never emitting `Wait`/`Acq`.
+    pub fn try_write(&self, place: &str) -> TryLockResult<IRwLockWriteGuard<T>> {
+        // No same-thread reentrancy check here: see the comment in
+        // try_read. A thread holding a read guard is allowed to call
+        // try_write as an opportunistic upgrade attempt; it must get
+        // Err(WouldBlock), not a deadlock panic.
+        #[cfg(not(feature = "lock-trace"))]
+        let _ = place;
+
+        #[cfg(feature = "lock-trace")]
+        error!("θ;{};Try;{};{};{}", self.name, 'W', self.thread(), place);
+        match self.inner.try_write() {
+            Ok(inner) => {
+                #[cfg(debug_assertions)]
+                self.register_writer();
+                #[cfg(feature = "lock-trace")]
+                error!("θ;{};Acq;{};{};{}", self.name, 'W', self.thread(), place);
+                Ok(IRwLockWriteGuard {
+                    inner,
+                    lock: self,
+                    #[cfg(feature = "lock-trace")]
+                    place: place.to_string(),
+                    acquired_at: Instant::now(),
+                })
+            }
+            Err(TryLockError::WouldBlock) => {
+                #[cfg(feature = "lock-trace")]
+                error!("θ;{};NoAcq;{};{};{}", self.name, 'W', self.thread(), place);
+                Err(TryLockError::WouldBlock)
+            }
+            Err(TryLockError::Poisoned(err)) => {
+                #[cfg(debug_assertions)]
+                self.register_writer();
+                let guard = IRwLockWriteGuard {
+                    inner: err.into_inner(),
+                    lock: self,
+                    #[cfg(feature = "lock-trace")]
+                    place: place.to_string(),
+                    acquired_at: Instant::now(),
+                };
+                if self.recover_from_poison {
+                    self.log_poison_once();
+                    #[cfg(feature = "lock-trace")]
+                    error!("θ;{};Acq;{};{};{}", self.name, 'W', self.thread(), place);
+                    Ok(guard)
+                } else {
+                    #[cfg(feature = "lock-trace")]
+                    error!("θ;{};NoAcq;{};{};{}", self.name, 'W', self.thread(), place);
+                    Err(TryLockError::Poisoned(PoisonError::new(guard)))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    #[should_panic(expected = "deadlock detected")]
+    fn test_write_then_read_same_thread_panics() {
+        let lock = IRwLock::new("test".to_string(), 0);
+        let _write_guard = lock.write("first").expect("lock should not be poisoned");
+        let _read_guard = lock.read("second");
+    }
+
+    #[test]
+    fn test_new_unpoisonable_recovers_after_poisoning_panic() {
+        let lock = Arc::new(IRwLock::new_unpoisonable("test".to_string(), 0));
+        let poisoner = Arc::clone(&lock);
+
+        let result = thread::spawn(move || {
+            let _guard = poisoner.write("poisoner").expect("lock should not be poisoned yet");
+            panic!("intentional panic to poison the lock");
         })
+        .join();
+        assert!(result.is_err(), "the spawned thread should have panicked");
+
+        let guard = lock
+            .read("after-poison")
+            .expect("new_unpoisonable should transparently recover from poisoning");
+        assert_eq!(*guard, 0);
+    }
+
+    #[test]
+    fn test_slow_hold_threshold_does_not_panic_or_block_release() {
+        // The slow-hold check runs unconditionally (not just under the
+        // `lock-trace` feature); this just exercises that holding past the
+        // threshold logs a warning on drop instead of panicking or wedging
+        // the lock.
+        let lock = IRwLock::new_with_slow_hold_threshold(
+            "test".to_string(),
+            0,
+            Duration::from_millis(0),
+        );
+        {
+            let _guard = lock.write("slow").expect("lock should not be poisoned");
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(*lock.read("after").expect("lock should not be poisoned"), 0);
     }
 }
 //create new type for ilock with the same interface as python's